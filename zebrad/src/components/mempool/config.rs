@@ -0,0 +1,29 @@
+//! Configuration for the mempool.
+
+use serde::{Deserialize, Serialize};
+
+/// The default cost limit for the mempool, as defined by ZIP-401.
+///
+/// `DEFAULT_MEMPOOL_TOTAL_COST_LIMIT` in the ZIP.
+pub const DEFAULT_TX_COST_LIMIT: u64 = 80_000_000;
+
+/// Mempool configuration section.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The total cost limit for the transactions in the mempool, as defined
+    /// in [ZIP-401](https://zips.z.cash/zip-0401).
+    ///
+    /// Once the sum of the `cost` of the verified transactions would exceed this
+    /// limit, the mempool evicts transactions until it is back under the limit,
+    /// picking which ones to evict via weighted random selection over `eviction_weight`.
+    pub tx_cost_limit: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tx_cost_limit: DEFAULT_TX_COST_LIMIT,
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Download and verification of mempool transactions gossiped by ID.
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    future::{self, BoxFuture, Either},
+    stream::FuturesUnordered,
+    FutureExt, Stream,
+};
+use tokio::sync::oneshot;
+use tower::{Service, ServiceExt};
+
+use zebra_chain::transaction::{UnminedTx, UnminedTxId};
+use zebra_consensus::transaction as tx;
+use zebra_network as zn;
+
+use super::{storage::VerifiedUnminedTx, MempoolError};
+
+/// A transaction gossiped to the mempool, either by its full bytes or by id,
+/// in which case it must be fetched from the network before it can be
+/// verified.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Gossip {
+    /// A transaction we already have the bytes for.
+    Tx(UnminedTx),
+    /// A transaction we've only seen the id of, and must download.
+    Id(UnminedTxId),
+}
+
+impl Gossip {
+    /// The id of the gossiped transaction.
+    pub fn id(&self) -> UnminedTxId {
+        match self {
+            Gossip::Tx(tx) => tx.id,
+            Gossip::Id(id) => *id,
+        }
+    }
+}
+
+impl From<UnminedTx> for Gossip {
+    fn from(tx: UnminedTx) -> Self {
+        Gossip::Tx(tx)
+    }
+}
+
+impl From<UnminedTxId> for Gossip {
+    fn from(id: UnminedTxId) -> Self {
+        Gossip::Id(id)
+    }
+}
+
+/// The output of a single download-and-verify task.
+type DownloadResult = Result<VerifiedUnminedTx, (UnminedTxId, MempoolError)>;
+
+/// Manages download and verification of transactions that were gossiped to
+/// the mempool, running one future per in-flight transaction. Transactions
+/// gossiped by ID are downloaded first; transactions gossiped with their full
+/// bytes are only verified.
+///
+/// Each future can be cancelled, which is used to stop downloading or
+/// verifying a transaction that is no longer relevant, for example because
+/// the chain tip changed.
+pub struct Downloads<ZN, ZV>
+where
+    ZN: Service<zn::Request, Response = zn::Response, Error = zn::BoxError> + Clone + Send + 'static,
+    ZN::Future: Send,
+    ZV: Service<tx::Request, Response = tx::Response, Error = tx::Error> + Clone + Send + 'static,
+    ZV::Future: Send,
+{
+    network: ZN,
+    verifier: ZV,
+
+    /// The in-flight download-and-verify futures, each wrapped so it resolves
+    /// to `None` if its cancel handle is dropped or fired.
+    pending: FuturesUnordered<BoxFuture<'static, Option<(UnminedTxId, DownloadResult)>>>,
+
+    /// Cancel handles for `pending`, by transaction id.
+    cancel_handles: HashMap<UnminedTxId, oneshot::Sender<()>>,
+}
+
+impl<ZN, ZV> Downloads<ZN, ZV>
+where
+    ZN: Service<zn::Request, Response = zn::Response, Error = zn::BoxError> + Clone + Send + 'static,
+    ZN::Future: Send,
+    ZV: Service<tx::Request, Response = tx::Response, Error = tx::Error> + Clone + Send + 'static,
+    ZV::Future: Send,
+{
+    /// Create a new download stream, fetching unknown transactions using
+    /// `network` and verifying them using `verifier`.
+    pub fn new(network: ZN, verifier: ZV) -> Self {
+        Self {
+            network,
+            verifier,
+            pending: FuturesUnordered::new(),
+            cancel_handles: HashMap::new(),
+        }
+    }
+
+    /// The number of transactions currently being downloaded or verified.
+    pub fn in_flight(&self) -> usize {
+        self.cancel_handles.len()
+    }
+
+    /// Queue the download (if necessary) and verification of `gossip`,
+    /// unless it is already in flight.
+    pub fn download_if_necessary(&mut self, gossip: Gossip) {
+        let txid = gossip.id();
+
+        if self.cancel_handles.contains_key(&txid) {
+            return;
+        }
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        self.cancel_handles.insert(txid, cancel_tx);
+
+        let task = Self::fetch_and_verify(self.network.clone(), self.verifier.clone(), gossip)
+            .map(move |result| (txid, result));
+
+        let cancellable_task = async move {
+            match future::select(task.boxed(), cancel_rx).await {
+                Either::Left((output, _)) => Some(output),
+                Either::Right(_) => None,
+            }
+        };
+
+        self.pending.push(cancellable_task.boxed());
+    }
+
+    /// Cancel the download/verification of the transactions in `txids`.
+    pub fn cancel(&mut self, txids: &std::collections::HashSet<UnminedTxId>) {
+        for txid in txids {
+            if let Some(handle) = self.cancel_handles.remove(txid) {
+                let _ = handle.send(());
+            }
+        }
+    }
+
+    /// Cancel every download/verification currently in flight.
+    pub fn cancel_all(&mut self) {
+        for (_, handle) in self.cancel_handles.drain() {
+            let _ = handle.send(());
+        }
+    }
+
+    /// Fetch `gossip`'s transaction bytes over `network` if necessary, then
+    /// verify them with `verifier`.
+    async fn fetch_and_verify(
+        mut network: ZN,
+        mut verifier: ZV,
+        gossip: Gossip,
+    ) -> DownloadResult {
+        let txid = gossip.id();
+
+        let unmined_tx = match gossip {
+            Gossip::Tx(tx) => tx,
+            Gossip::Id(id) => {
+                let ids = std::iter::once(id).collect();
+
+                let response = network
+                    .ready()
+                    .await
+                    .map_err(|_| (txid, MempoolError::Disabled))?
+                    .call(zn::Request::TransactionsById(ids))
+                    .await
+                    .map_err(|_| (txid, MempoolError::Disabled))?;
+
+                match response {
+                    zn::Response::Transactions(mut txs) if !txs.is_empty() => txs.remove(0),
+                    _ => return Err((txid, MempoolError::Disabled)),
+                }
+            }
+        };
+
+        let verifier_response = verifier
+            .ready()
+            .await
+            .map_err(|_| (txid, MempoolError::Disabled))?
+            .call(tx::Request::Mempool {
+                transaction: unmined_tx.clone(),
+            })
+            .await;
+
+        match verifier_response {
+            Ok(tx::Response::Mempool { miner_fee, .. }) => {
+                Ok(VerifiedUnminedTx::new(unmined_tx, miner_fee))
+            }
+            Ok(_) => unreachable!("verifier only returns Mempool responses for Mempool requests"),
+            Err(error) => Err((
+                txid,
+                super::ExactTipRejectionError::FailedVerification(error).into(),
+            )),
+        }
+    }
+}
+
+impl<ZN, ZV> Stream for Downloads<ZN, ZV>
+where
+    ZN: Service<zn::Request, Response = zn::Response, Error = zn::BoxError> + Clone + Send + 'static,
+    ZN::Future: Send,
+    ZV: Service<tx::Request, Response = tx::Response, Error = tx::Error> + Clone + Send + 'static,
+    ZV::Future: Send,
+{
+    type Item = (UnminedTxId, DownloadResult);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.pending).poll_next(cx) {
+                Poll::Ready(Some(Some((txid, result)))) => {
+                    self.cancel_handles.remove(&txid);
+                    return Poll::Ready(Some((txid, result)));
+                }
+                // The task was cancelled: keep polling for the next one.
+                Poll::Ready(Some(None)) => continue,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Errors that can occur when interacting with the mempool.
+
+use thiserror::Error;
+
+use zebra_consensus::transaction as tx;
+
+/// An error that can occur when interacting with the mempool.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+#[allow(dead_code)]
+pub enum MempoolError {
+    /// The mempool is not active.
+    #[error("mempool is not active")]
+    Disabled,
+
+    /// The transaction was rejected because of a rejection that only applies
+    /// while the chain tip doesn't change, keyed by the transaction's effects.
+    #[error(transparent)]
+    StorageEffectsTip(#[from] SameEffectsTipRejectionError),
+
+    /// The transaction was rejected because of a temporary rejection keyed by
+    /// the transaction's exact bytes: a failed verification, which applies
+    /// until the chain tip changes, or a recent eviction, which applies until
+    /// its chain-time expiry.
+    #[error(transparent)]
+    StorageExactTip(#[from] ExactTipRejectionError),
+}
+
+/// Rejection errors that apply to a transaction's effects, and prevent it from
+/// being re-queued until the tip changes.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum SameEffectsTipRejectionError {
+    /// The transaction is already in the mempool.
+    #[error("transaction is already in the mempool")]
+    AlreadyInMempool,
+
+    /// The transaction double-spends an input that is already spent by a
+    /// verified mempool transaction.
+    #[error("transaction double-spends inputs that are already spent in the mempool")]
+    SpendConflict,
+}
+
+/// Rejection errors that apply to the exact transaction bytes, and prevent
+/// that exact transaction from being re-queued for a while: until the tip
+/// changes for [`ExactTipRejectionError::FailedVerification`], or until the
+/// eviction entry expires in chain time for [`ExactTipRejectionError::RandomlyEvicted`].
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum ExactTipRejectionError {
+    /// The transaction failed consensus validation.
+    #[error("transaction did not pass consensus validation")]
+    FailedVerification(#[from] tx::Error),
+
+    /// The transaction was evicted from the mempool due to the ZIP-401
+    /// random eviction mechanism, and is temporarily rejected to avoid it
+    /// being immediately re-downloaded and re-verified.
+    #[error("transaction was recently evicted from the mempool")]
+    RandomlyEvicted,
+}
@@ -0,0 +1,323 @@
+//! The mempool task and service.
+//!
+//! The mempool is the set of unmined transactions that this node is aware
+//! of and considers valid. Transactions enter the mempool either because a
+//! peer gossiped their id (and we downloaded and verified them), or because
+//! a client submitted them directly.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{future::BoxFuture, FutureExt, Stream};
+use tower::Service;
+
+use zebra_chain::transaction::{self, UnminedTx, UnminedTxId};
+use zebra_consensus::transaction as tx;
+use zebra_state::{ChainTipChange, TipAction};
+
+use self::downloads::{Downloads, Gossip};
+pub use self::{
+    config::Config,
+    error::{ExactTipRejectionError, MempoolError, SameEffectsTipRejectionError},
+    storage::Storage,
+};
+
+// Re-exported so that `mempool::*` gives test code (and other callers that
+// build `Mempool`s by hand) the same names this module builds its service
+// types from.
+pub use tower::{buffer::Buffer, util::BoxService};
+pub use zebra_consensus::error::TransactionError;
+pub use zebra_network as zn;
+pub use zebra_state as zs;
+
+use super::sync::{RecentSyncLengths, SyncStatus};
+
+pub mod config;
+mod downloads;
+pub mod error;
+pub mod storage;
+
+#[cfg(test)]
+pub mod tests;
+
+/// The peer set used to fetch transactions that were only gossiped by id.
+type Outbound = Buffer<BoxService<zn::Request, zn::Response, zn::BoxError>, zn::Request>;
+
+/// The state service used to look up the chain tip.
+type StateService = Buffer<BoxService<zs::Request, zs::Response, zs::BoxError>, zs::Request>;
+
+/// The transaction verifier service used to verify downloaded transactions.
+type TxVerifierService = Buffer<BoxService<tx::Request, tx::Response, tx::Error>, tx::Request>;
+
+/// A request to the mempool service.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Request {
+    /// Get the ids of all the transactions currently in the mempool.
+    TransactionIds,
+
+    /// Queue one or more transactions, by id or by their full bytes, for
+    /// download (if necessary) and verification.
+    Queue(Vec<Gossip>),
+
+    /// Look up transactions in the mempool by their unmined ID, so they can
+    /// be served to RPC clients or gossiped to peers. Ids that aren't found
+    /// are omitted from the response.
+    TransactionsById(HashSet<UnminedTxId>),
+
+    /// Look up transactions in the mempool by their legacy mined ID, for
+    /// callers that don't have the unmined ID (for example RPC clients
+    /// calling `getrawtransaction` with just a txid). Ids that aren't found
+    /// are omitted from the response.
+    TransactionsByMinedId(HashSet<transaction::Hash>),
+}
+
+/// A response from the mempool service.
+#[derive(Debug)]
+pub enum Response {
+    /// The ids of the transactions currently in the mempool.
+    TransactionIds(HashSet<UnminedTxId>),
+
+    /// The outcome of queueing each transaction in a [`Request::Queue`], in
+    /// the same order as the request.
+    Queued(Vec<Result<(), MempoolError>>),
+
+    /// The transactions found for a [`Request::TransactionsById`] or
+    /// [`Request::TransactionsByMinedId`].
+    Transactions(Vec<UnminedTx>),
+}
+
+/// Whether the mempool is currently enabled.
+///
+/// The mempool is disabled while Zebra is a long way behind the chain tip,
+/// since there is no point in tracking unmined transactions until we're
+/// close to synced.
+#[derive(Debug, PartialEq, Eq)]
+enum ActiveState {
+    /// The mempool is enabled, and is tracking unmined transactions.
+    Enabled,
+    /// The mempool is disabled, and drops any transactions it is asked about.
+    Disabled,
+}
+
+/// The mempool service.
+pub struct Mempool {
+    /// Whether the mempool is enabled or disabled.
+    active_state: ActiveState,
+
+    /// The set of verified and recently-rejected transactions.
+    storage: Storage,
+
+    /// The in-flight transaction downloads and verifications.
+    tx_downloads: Downloads<Outbound, TxVerifierService>,
+
+    /// The state service, used to look up the chain tip.
+    #[allow(dead_code)]
+    state: StateService,
+
+    /// A watcher for when the synchronizer is likely close to the network tip.
+    sync_status: SyncStatus,
+
+    /// The latest chain tip, as reported by the state.
+    latest_chain_tip: zs::LatestChainTip,
+
+    /// A watcher for chain tip changes, used to cancel downloads of mined
+    /// transactions and to evict expired ones.
+    chain_tip_change: ChainTipChange,
+
+    /// A channel used to gossip the ids of newly verified transactions to
+    /// the rest of `zebrad`, so they can be announced to peers.
+    transaction_sender: tokio::sync::mpsc::Sender<UnminedTxId>,
+}
+
+impl Mempool {
+    /// Create a new mempool service, and return its transaction receiver.
+    ///
+    /// The returned receiver is used elsewhere in `zebrad` to gossip newly
+    /// verified transactions to peers.
+    pub fn new(
+        config: &Config,
+        outbound: Outbound,
+        state: StateService,
+        tx_verifier: TxVerifierService,
+        sync_status: SyncStatus,
+        latest_chain_tip: zs::LatestChainTip,
+        chain_tip_change: ChainTipChange,
+    ) -> (Self, tokio::sync::mpsc::Receiver<UnminedTxId>) {
+        let (transaction_sender, receiver) = tokio::sync::mpsc::channel(100);
+
+        let mempool = Mempool {
+            active_state: ActiveState::Disabled,
+            storage: Storage::new(config.tx_cost_limit),
+            tx_downloads: Downloads::new(outbound, tx_verifier),
+            state,
+            sync_status,
+            latest_chain_tip,
+            chain_tip_change,
+            transaction_sender,
+        };
+
+        (mempool, receiver)
+    }
+
+    /// Enable the mempool.
+    pub async fn enable(&mut self, _recent_syncs: &mut RecentSyncLengths) {
+        self.active_state = ActiveState::Enabled;
+    }
+
+    /// Disable the mempool, dropping all the transactions it was tracking.
+    pub async fn disable(&mut self, _recent_syncs: &mut RecentSyncLengths) {
+        self.active_state = ActiveState::Disabled;
+        self.storage = Storage::new(self.storage.tx_cost_limit());
+        self.tx_downloads.cancel_all();
+    }
+
+    /// Returns `true` if the mempool is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.active_state == ActiveState::Enabled
+    }
+
+    /// Direct access to the mempool's storage, used in tests that bypass the
+    /// [`Request`]/[`Response`] API.
+    pub fn storage(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Direct access to the mempool's in-flight downloads, used in tests.
+    pub fn tx_downloads(&self) -> &Downloads<Outbound, TxVerifierService> {
+        &self.tx_downloads
+    }
+
+    /// Poll the service once, ignoring the result, just to drive its
+    /// internal futures forward.
+    #[cfg(test)]
+    pub async fn dummy_call(&mut self) {
+        use tower::ServiceExt;
+
+        let _ = self
+            .ready_and()
+            .await
+            .expect("mempool never fails to become ready")
+            .call(Request::TransactionIds)
+            .await;
+    }
+
+    /// Check whether the chain tip has changed since we last looked, and if
+    /// so, cancel any in-flight downloads for transactions that may have
+    /// just been mined, and drop verified transactions whose `expiry_height`
+    /// has passed, since they can never be mined.
+    fn poll_tip_change(&mut self) {
+        if let Some(tip_action) = self.chain_tip_change.last_tip_change() {
+            let new_tip_height = match &tip_action {
+                TipAction::Grow { block } => block.height,
+                TipAction::Reset { height, .. } => *height,
+            };
+
+            // Drive the recently-evicted rejection list's expiry off chain
+            // time (the new tip's timestamp), not wall-clock time, so it
+            // tracks the chain rather than however fast we happen to be
+            // downloading blocks.
+            if let TipAction::Grow { block } = &tip_action {
+                self.storage.tick_chain_time(block.time);
+            }
+
+            self.tx_downloads.cancel_all();
+            self.storage.clear_tip_rejections();
+            self.storage.remove_expired_transactions(new_tip_height);
+        }
+    }
+
+    /// Queue a single gossiped transaction, returning an error immediately if
+    /// it is already known to be invalid or was recently evicted.
+    fn queue_one(&mut self, gossip: Gossip) -> Result<(), MempoolError> {
+        let txid = gossip.id();
+
+        if let Some(error) = self.storage.rejection_error(&txid) {
+            return Err(error);
+        }
+
+        self.tx_downloads.download_if_necessary(gossip);
+
+        Ok(())
+    }
+}
+
+impl Service<Request> for Mempool {
+    type Response = Response;
+    type Error = tower::BoxError;
+    type Future = BoxFuture<'static, Result<Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_tip_change();
+
+        // Drain any downloads/verifications that have finished, moving
+        // successful ones into storage.
+        while let Poll::Ready(Some((txid, result))) =
+            Pin::new(&mut self.tx_downloads).poll_next(cx)
+        {
+            match result {
+                Ok(verified_tx) => {
+                    if let Ok(inserted_id) = self.storage.insert(verified_tx) {
+                        // The channel is bounded, and dropped/lagging
+                        // receivers shouldn't stop the mempool from working.
+                        let _ = self.transaction_sender.try_send(inserted_id);
+                    }
+                }
+                Err((_, MempoolError::StorageExactTip(error))) => {
+                    self.storage.reject(txid, error);
+                }
+                Err(_) => {
+                    // Download failures are not rejected, so they can be retried.
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if !self.is_enabled() {
+            let response = match req {
+                Request::TransactionIds => Response::TransactionIds(HashSet::new()),
+                Request::Queue(gossips) => {
+                    Response::Queued(gossips.iter().map(|_| Err(MempoolError::Disabled)).collect())
+                }
+                Request::TransactionsById(_) | Request::TransactionsByMinedId(_) => {
+                    Response::Transactions(Vec::new())
+                }
+            };
+
+            return async move { Ok(response) }.boxed();
+        }
+
+        match req {
+            Request::TransactionIds => {
+                let ids = self.storage.tx_ids().copied().collect();
+                async move { Ok(Response::TransactionIds(ids)) }.boxed()
+            }
+            Request::Queue(gossips) => {
+                let results = gossips
+                    .into_iter()
+                    .map(|gossip| self.queue_one(gossip))
+                    .collect();
+
+                async move { Ok(Response::Queued(results)) }.boxed()
+            }
+            Request::TransactionsById(ids) => {
+                let transactions = self.storage.transactions_by_id(&ids).cloned().collect();
+                async move { Ok(Response::Transactions(transactions)) }.boxed()
+            }
+            Request::TransactionsByMinedId(ids) => {
+                let transactions = self
+                    .storage
+                    .transactions_by_mined_id(&ids)
+                    .cloned()
+                    .collect();
+                async move { Ok(Response::Transactions(transactions)) }.boxed()
+            }
+        }
+    }
+}
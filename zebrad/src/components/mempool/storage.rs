@@ -0,0 +1,340 @@
+//! The mempool's storage of verified and temporarily-rejected transactions.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::{Distribution, WeightedIndex};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::Height,
+    transaction::{UnminedTx, UnminedTxId},
+};
+
+use super::error::{ExactTipRejectionError, MempoolError, SameEffectsTipRejectionError};
+
+#[cfg(test)]
+pub mod tests;
+
+/// The minimum cost, in bytes, assigned to any transaction for the purposes
+/// of the ZIP-401 eviction mechanism, regardless of its real serialized size.
+const MEMPOOL_TRANSACTION_COST_THRESHOLD: u64 = 4_000;
+
+/// The conventional fee rate used to decide whether a transaction pays a low
+/// fee, in zatoshi per `MEMPOOL_TRANSACTION_COST_FEE_BASIS` bytes of cost, as
+/// defined by ZIP-401.
+const MEMPOOL_TRANSACTION_CONVENTIONAL_FEE: u64 = 1_000;
+
+/// The number of bytes of cost that `MEMPOOL_TRANSACTION_CONVENTIONAL_FEE`
+/// zatoshi buys, as defined by ZIP-401.
+const MEMPOOL_TRANSACTION_COST_FEE_BASIS: u64 = 1_000;
+
+/// The extra weight added to a transaction's `eviction_weight` when it pays
+/// less than the conventional fee, as defined by ZIP-401.
+const MEMPOOL_TRANSACTION_LOW_FEE_PENALTY: u64 = 16_000;
+
+/// How long an evicted transaction is kept in the temporary rejection list,
+/// before it becomes eligible to be queued again. Measured in chain time
+/// (the timestamp of the best chain tip), not wall-clock time, since the
+/// latter can drift arbitrarily far from the chain during initial block
+/// download.
+fn evicted_transaction_expiry() -> Duration {
+    Duration::seconds(60)
+}
+
+/// A mempool transaction that has already passed contextual and non-contextual
+/// verification, along with the miner fee it pays.
+///
+/// This is the unit that [`Storage`] stores and that the ZIP-401 eviction
+/// mechanism operates on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedUnminedTx {
+    /// The unmined transaction itself.
+    pub transaction: UnminedTx,
+
+    /// The fee the transaction pays, as verified against the state.
+    pub miner_fee: Amount<NonNegative>,
+}
+
+impl VerifiedUnminedTx {
+    /// Create a new [`VerifiedUnminedTx`] from an unmined transaction and
+    /// the fee it was verified to pay.
+    pub fn new(transaction: UnminedTx, miner_fee: Amount<NonNegative>) -> Self {
+        Self {
+            transaction,
+            miner_fee,
+        }
+    }
+
+    /// The cost of this transaction, for the purposes of the ZIP-401
+    /// eviction mechanism: its serialized size, but never less than
+    /// [`MEMPOOL_TRANSACTION_COST_THRESHOLD`].
+    pub fn cost(&self) -> u64 {
+        (self.transaction.size as u64).max(MEMPOOL_TRANSACTION_COST_THRESHOLD)
+    }
+
+    /// The conventional fee for a transaction of this size, as defined by
+    /// ZIP-401: `cost * 1000 / 1000` zatoshi.
+    pub fn conventional_fee(&self) -> u64 {
+        self.cost() * MEMPOOL_TRANSACTION_CONVENTIONAL_FEE / MEMPOOL_TRANSACTION_COST_FEE_BASIS
+    }
+
+    /// Returns `true` if this transaction pays less than its
+    /// [`VerifiedUnminedTx::conventional_fee`].
+    pub fn has_low_fee(&self) -> bool {
+        u64::from(self.miner_fee) < self.conventional_fee()
+    }
+
+    /// The weight used to pick this transaction for random eviction, as
+    /// defined by ZIP-401: its `cost`, plus a penalty if it pays a low fee.
+    pub fn eviction_weight(&self) -> u64 {
+        let low_fee_penalty = if self.has_low_fee() {
+            MEMPOOL_TRANSACTION_LOW_FEE_PENALTY
+        } else {
+            0
+        };
+
+        self.cost() + low_fee_penalty
+    }
+}
+
+/// A list of recently-evicted transaction IDs, which are rejected from the
+/// mempool until their entry expires.
+///
+/// This implements the "keep the transaction ID in the rejection filter for
+/// at least one minute" requirement from
+/// [ZIP-401](https://zips.z.cash/zip-0401).
+#[derive(Default)]
+struct EvictionList {
+    /// The chain-time expiry of each recently-evicted transaction.
+    expiries: HashMap<UnminedTxId, DateTime<Utc>>,
+}
+
+impl EvictionList {
+    /// Record that `txid` was just evicted at `chain_time`, starting its
+    /// expiry timer.
+    fn insert(&mut self, txid: UnminedTxId, chain_time: DateTime<Utc>) {
+        self.expiries
+            .insert(txid, chain_time + evicted_transaction_expiry());
+    }
+
+    /// Returns `true` if `txid` was recently evicted and hasn't expired yet,
+    /// as of `chain_time`.
+    fn contains(&mut self, txid: &UnminedTxId, chain_time: DateTime<Utc>) -> bool {
+        self.prune_expired(chain_time);
+        self.expiries.contains_key(txid)
+    }
+
+    /// Remove all entries whose expiry has passed as of `chain_time`.
+    fn prune_expired(&mut self, chain_time: DateTime<Utc>) {
+        self.expiries.retain(|_, expiry| *expiry > chain_time);
+    }
+}
+
+/// The mempool's in-memory storage of verified transactions, plus the
+/// temporary rejection lists used to avoid immediately re-verifying
+/// transactions that were just rejected or evicted.
+pub struct Storage {
+    /// The set of verified transactions, indexed by their unmined ID.
+    verified: HashMap<UnminedTxId, VerifiedUnminedTx>,
+
+    /// Transactions that failed verification against the current tip, kept
+    /// until the tip changes.
+    rejected_exact_tip: HashMap<UnminedTxId, ExactTipRejectionError>,
+
+    /// Transactions that were evicted from `verified` by the ZIP-401 cost
+    /// limit, kept for a short time so they aren't immediately re-downloaded.
+    evicted: EvictionList,
+
+    /// The sum of `VerifiedUnminedTx::cost()` for every transaction in `verified`.
+    total_cost: u64,
+
+    /// The configured cost limit, from [`Config::tx_cost_limit`](super::Config::tx_cost_limit).
+    tx_cost_limit: u64,
+
+    /// The timestamp of the best chain tip, as last reported via
+    /// [`Storage::tick_chain_time`]. Used as the clock for `evicted`'s
+    /// expiry, so that eviction rejections expire with chain progress
+    /// instead of wall-clock time.
+    chain_time: DateTime<Utc>,
+}
+
+impl Storage {
+    /// Create a new, empty storage, enforcing `tx_cost_limit` as the total
+    /// cost limit for the mempool, as defined by ZIP-401.
+    pub fn new(tx_cost_limit: u64) -> Self {
+        Self {
+            verified: HashMap::default(),
+            rejected_exact_tip: HashMap::default(),
+            evicted: EvictionList::default(),
+            total_cost: 0,
+            tx_cost_limit,
+            // There is no chain tip yet, so there's nothing for evictions to
+            // expire against until the first tip is observed.
+            chain_time: Utc::now(),
+        }
+    }
+
+    /// Record `chain_time` as the timestamp of the current best chain tip,
+    /// called whenever the tip grows by a block (a tip reset leaves the
+    /// recorded chain time unchanged). Drives the expiry of the recently
+    /// evicted transaction list.
+    pub fn tick_chain_time(&mut self, chain_time: DateTime<Utc>) {
+        self.chain_time = chain_time;
+    }
+
+    /// Insert a verified transaction into the mempool, then evict
+    /// transactions at random, weighted by [`VerifiedUnminedTx::eviction_weight`],
+    /// until the total cost of the mempool is back under `tx_cost_limit`.
+    ///
+    /// Returns an error without modifying storage if `tx` was already
+    /// verified, rejected, or recently evicted.
+    pub fn insert(&mut self, tx: VerifiedUnminedTx) -> Result<UnminedTxId, MempoolError> {
+        let tx_id = tx.transaction.id;
+
+        if let Some(error) = self.rejection_error(&tx_id) {
+            return Err(error);
+        }
+
+        if self.verified.contains_key(&tx_id) {
+            return Err(SameEffectsTipRejectionError::AlreadyInMempool.into());
+        }
+
+        self.total_cost += tx.cost();
+        self.verified.insert(tx_id, tx);
+
+        self.evict_over_capacity();
+
+        Ok(tx_id)
+    }
+
+    /// Mark `tx_id` as having failed verification, so it is rejected until
+    /// the tip changes.
+    pub fn reject(&mut self, tx_id: UnminedTxId, error: ExactTipRejectionError) {
+        self.rejected_exact_tip.insert(tx_id, error);
+    }
+
+    /// The configured total cost limit for this storage, as defined by
+    /// [`Config::tx_cost_limit`](super::Config::tx_cost_limit).
+    pub fn tx_cost_limit(&self) -> u64 {
+        self.tx_cost_limit
+    }
+
+    /// Returns the rejection error for `tx_id`, if it is currently rejected
+    /// because it failed verification or was recently evicted.
+    pub fn rejection_error(&mut self, tx_id: &UnminedTxId) -> Option<MempoolError> {
+        if let Some(error) = self.rejected_exact_tip.get(tx_id) {
+            return Some(error.clone().into());
+        }
+
+        if self.evicted.contains(tx_id, self.chain_time) {
+            return Some(ExactTipRejectionError::RandomlyEvicted.into());
+        }
+
+        None
+    }
+
+    /// Remove `tx_id` from the verified set, without adding it to any
+    /// rejection list, so it can be re-queued immediately.
+    ///
+    /// Used to drop transactions whose `expiry_height` has passed.
+    pub fn remove(&mut self, tx_id: &UnminedTxId) -> Option<VerifiedUnminedTx> {
+        let removed = self.verified.remove(tx_id)?;
+        self.total_cost -= removed.cost();
+        Some(removed)
+    }
+
+    /// Clear the exact-tip rejection list, called whenever the tip changes.
+    pub fn clear_tip_rejections(&mut self) {
+        self.rejected_exact_tip.clear();
+    }
+
+    /// Remove every verified transaction whose `expiry_height` is non-zero
+    /// and has passed at `tip_height`, since such a transaction can never be
+    /// mined.
+    ///
+    /// Expired transactions are simply dropped, not added to any rejection
+    /// list, so they can be re-queued if a reorganization makes them valid
+    /// again. Returns the ids of the transactions that were removed.
+    pub fn remove_expired_transactions(&mut self, tip_height: Height) -> Vec<UnminedTxId> {
+        let expired_ids: Vec<UnminedTxId> = self
+            .verified
+            .values()
+            .filter(|tx| {
+                tx.transaction
+                    .transaction
+                    .expiry_height()
+                    .map_or(false, |expiry_height| {
+                        // `Height(0)` means the transaction opted out of expiry entirely.
+                        expiry_height != Height(0) && expiry_height <= tip_height
+                    })
+            })
+            .map(|tx| tx.transaction.id)
+            .collect();
+
+        for tx_id in &expired_ids {
+            self.remove(tx_id);
+        }
+
+        expired_ids
+    }
+
+    /// Returns the set of the ids of the verified transactions.
+    pub fn tx_ids(&self) -> impl Iterator<Item = &UnminedTxId> {
+        self.verified.keys()
+    }
+
+    /// Returns the verified transactions whose ids are in `ids`.
+    pub fn transactions_by_id<'a>(
+        &'a self,
+        ids: &'a std::collections::HashSet<UnminedTxId>,
+    ) -> impl Iterator<Item = &'a UnminedTx> {
+        ids.iter()
+            .filter_map(move |id| self.verified.get(id))
+            .map(|tx| &tx.transaction)
+    }
+
+    /// Returns the verified transactions whose legacy mined id is in `ids`.
+    pub fn transactions_by_mined_id<'a>(
+        &'a self,
+        ids: &'a std::collections::HashSet<zebra_chain::transaction::Hash>,
+    ) -> impl Iterator<Item = &'a UnminedTx> {
+        self.verified
+            .values()
+            .map(|tx| &tx.transaction)
+            .filter(move |tx| ids.contains(&tx.transaction.hash()))
+    }
+
+    /// Evict transactions, picked by weighted random selection, until
+    /// `total_cost` is back under `tx_cost_limit`.
+    fn evict_over_capacity(&mut self) {
+        let mut rng = rand::thread_rng();
+
+        while self.total_cost > self.tx_cost_limit {
+            let weights: Vec<u64> = self
+                .verified
+                .values()
+                .map(VerifiedUnminedTx::eviction_weight)
+                .collect();
+
+            if weights.is_empty() {
+                break;
+            }
+
+            let distribution =
+                WeightedIndex::new(&weights).expect("at least one transaction with nonzero cost");
+            let index = distribution.sample(&mut rng);
+
+            let evicted_id = *self
+                .verified
+                .keys()
+                .nth(index)
+                .expect("sampled index is within bounds");
+
+            if let Some(evicted_tx) = self.verified.remove(&evicted_id) {
+                self.total_cost -= evicted_tx.cost();
+                self.evicted.insert(evicted_id, self.chain_time);
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Test helpers for mempool storage tests.
+
+use std::{convert::TryFrom, ops::RangeBounds};
+
+use zebra_chain::{
+    amount::Amount, block::Block, parameters::Network, serialization::ZcashDeserializeInto,
+    transaction::UnminedTx,
+};
+
+use super::VerifiedUnminedTx;
+
+/// Return an iterator over the transactions in the `network` blocks at the
+/// heights in `range`, wrapped as [`VerifiedUnminedTx`]s.
+///
+/// This includes each block's coinbase transaction: the earliest mainnet
+/// blocks only contain a coinbase transaction, and the existing mempool
+/// tests rely on those being returned as usable (if unrealistic) test
+/// transactions.
+///
+/// The wrapped transactions all have a zero miner fee, since the test
+/// vectors don't include the information needed to compute a real fee.
+/// Tests that care about the fee should construct their own
+/// [`VerifiedUnminedTx`] instead.
+pub fn unmined_transactions_in_blocks(
+    range: impl RangeBounds<u32>,
+    network: Network,
+) -> impl DoubleEndedIterator<Item = VerifiedUnminedTx> {
+    let blocks = match network {
+        Network::Mainnet => zebra_test::vectors::MAINNET_BLOCKS.iter(),
+        Network::Testnet => zebra_test::vectors::TESTNET_BLOCKS.iter(),
+    };
+
+    blocks
+        .filter(move |(height, _)| range.contains(height))
+        .flat_map(|(_, block_bytes)| {
+            let block: Block = block_bytes
+                .zcash_deserialize_into()
+                .expect("block test vector is structurally valid");
+
+            block
+                .transactions
+                .iter()
+                .map(|tx| {
+                    VerifiedUnminedTx::new(
+                        UnminedTx::from(tx.clone()),
+                        Amount::try_from(0).expect("zero is a valid amount"),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
@@ -1,12 +1,19 @@
 //! Fixed test vectors for the mempool.
 
-use std::sync::Arc;
+use std::{convert::TryFrom, sync::Arc};
 
+use chrono::{TimeZone, Utc};
 use color_eyre::Report;
 use tokio::time;
 use tower::{ServiceBuilder, ServiceExt};
 
-use zebra_chain::{block::Block, parameters::Network, serialization::ZcashDeserializeInto};
+use zebra_chain::{
+    amount::Amount,
+    block::{Block, Height},
+    parameters::Network,
+    serialization::ZcashDeserializeInto,
+    transaction::{LockTime, Transaction, UnminedTx},
+};
 use zebra_consensus::transaction as tx;
 use zebra_state::Config as StateConfig;
 use zebra_test::mock_service::{MockService, PanicAssertion};
@@ -462,6 +469,232 @@ async fn mempool_failed_download_is_not_rejected() -> Result<(), Report> {
     Ok(())
 }
 
+/// Check that a transaction whose `expiry_height` has passed is dropped
+/// from the mempool once the chain tip reaches it, and that dropping it
+/// does not add it to any rejection list.
+#[tokio::test]
+async fn mempool_expired_transaction_is_removed() -> Result<(), Report> {
+    let block1: Arc<Block> = zebra_test::vectors::BLOCK_MAINNET_1_BYTES
+        .zcash_deserialize_into()
+        .unwrap();
+
+    let network = Network::Mainnet;
+
+    let (mut mempool, _peer_set, mut state_service, _tx_verifier, mut recent_syncs) =
+        setup(network).await;
+
+    time::pause();
+
+    let _ = mempool.enable(&mut recent_syncs).await;
+
+    // Push the genesis block to the state, since the downloader needs a valid tip.
+    let genesis_block: Arc<Block> = zebra_test::vectors::BLOCK_MAINNET_GENESIS_BYTES
+        .zcash_deserialize_into()
+        .unwrap();
+    state_service
+        .ready_and()
+        .await
+        .unwrap()
+        .call(zebra_state::Request::CommitFinalizedBlock(
+            genesis_block.clone().into(),
+        ))
+        .await
+        .unwrap();
+
+    // Query the mempool to make it poll chain_tip_change.
+    mempool.dummy_call().await;
+
+    // Build a transaction that expires as soon as the tip reaches height 1,
+    // the height of `block1`.
+    let expiring_transaction = Transaction::V4 {
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        lock_time: LockTime::Height(Height(0)),
+        expiry_height: Height(1),
+        joinsplit_data: None,
+        sapling_shielded_data: None,
+    };
+    let expiring_tx = VerifiedUnminedTx::new(
+        UnminedTx::from(Arc::new(expiring_transaction)),
+        Amount::try_from(0).expect("zero is a valid amount"),
+    );
+    let expiring_txid = expiring_tx.transaction.id;
+
+    mempool.storage().insert(expiring_tx)?;
+
+    let response = mempool
+        .ready_and()
+        .await
+        .unwrap()
+        .call(Request::TransactionIds)
+        .await
+        .unwrap();
+    match response {
+        Response::TransactionIds(ids) => assert!(ids.contains(&expiring_txid)),
+        _ => unreachable!("will never happen in this test"),
+    }
+
+    // Push block 1 to the state. Its height (1) reaches the transaction's
+    // expiry height, so `Mempool::poll_tip_change` should drop it.
+    state_service
+        .ready_and()
+        .await
+        .unwrap()
+        .call(zebra_state::Request::CommitFinalizedBlock(
+            block1.clone().into(),
+        ))
+        .await
+        .unwrap();
+
+    // Query the mempool to make it poll chain_tip_change.
+    mempool.dummy_call().await;
+
+    let response = mempool
+        .ready_and()
+        .await
+        .unwrap()
+        .call(Request::TransactionIds)
+        .await
+        .unwrap();
+    match response {
+        Response::TransactionIds(ids) => assert!(
+            !ids.contains(&expiring_txid),
+            "expired transaction should have been removed from the mempool"
+        ),
+        _ => unreachable!("will never happen in this test"),
+    }
+
+    // Dropping an expired transaction must not reject it, so it can be
+    // re-queued if a reorg makes it valid again.
+    assert_eq!(mempool.storage().rejection_error(&expiring_txid), None);
+
+    Ok(())
+}
+
+/// Check that [`Request::TransactionsById`] and
+/// [`Request::TransactionsByMinedId`] return the stored transactions that
+/// are present, and silently omit the ids that aren't.
+#[tokio::test]
+async fn mempool_transactions_by_id() -> Result<(), Report> {
+    let network = Network::Mainnet;
+
+    let (mut mempool, _peer_set, _state_service, _tx_verifier, mut recent_syncs) =
+        setup(network).await;
+
+    let mut unmined_transactions = unmined_transactions_in_blocks(1..=2, network);
+    let present_tx = unmined_transactions.next().unwrap();
+    let missing_txid = unmined_transactions.next().unwrap().transaction.id;
+
+    let _ = mempool.enable(&mut recent_syncs).await;
+
+    let present_txid = present_tx.transaction.id;
+    let present_mined_id = present_tx.transaction.transaction.hash();
+    mempool.storage().insert(present_tx.clone())?;
+
+    let response = mempool
+        .ready_and()
+        .await
+        .unwrap()
+        .call(Request::TransactionsById(
+            [present_txid, missing_txid].into_iter().collect(),
+        ))
+        .await
+        .unwrap();
+    let transactions = match response {
+        Response::Transactions(transactions) => transactions,
+        _ => unreachable!("will never happen in this test"),
+    };
+    assert_eq!(transactions, vec![present_tx.transaction.clone()]);
+
+    let response = mempool
+        .ready_and()
+        .await
+        .unwrap()
+        .call(Request::TransactionsByMinedId(
+            [present_mined_id].into_iter().collect(),
+        ))
+        .await
+        .unwrap();
+    let transactions = match response {
+        Response::Transactions(transactions) => transactions,
+        _ => unreachable!("will never happen in this test"),
+    };
+    assert_eq!(transactions, vec![present_tx.transaction]);
+
+    Ok(())
+}
+
+/// Check that transactions are evicted from storage once the total cost of
+/// the verified set exceeds the configured limit, and that evicted
+/// transactions are rejected for a short time afterwards, as required by
+/// [ZIP-401](https://zips.z.cash/zip-0401).
+#[tokio::test]
+async fn mempool_zip401_eviction() -> Result<(), Report> {
+    let network = Network::Mainnet;
+
+    let (mut mempool, _peer_set, _state_service, _tx_verifier, mut recent_syncs) =
+        setup(network).await;
+
+    let _ = mempool.enable(&mut recent_syncs).await;
+
+    // Use a tiny cost limit so a handful of test vector transactions overflow it.
+    let tx_cost_limit = 20_000;
+    *mempool.storage() = Storage::new(tx_cost_limit);
+
+    // The eviction rejection window is measured in chain time, not
+    // wall-clock time, so pin it to a known value instead of depending on
+    // whatever `Utc::now()` happens to be when this test runs.
+    let chain_time = Utc.timestamp(0, 0);
+    mempool.storage().tick_chain_time(chain_time);
+
+    let candidates: Vec<_> = unmined_transactions_in_blocks(1..=10, network).collect();
+    assert!(
+        candidates.len() >= 3,
+        "need several transactions to exercise eviction"
+    );
+
+    let mut inserted = Vec::new();
+    for tx in candidates {
+        inserted.push(tx.clone());
+        let _ = mempool.storage().insert(tx);
+    }
+
+    // The cost limit must have been enforced: some of the inserted
+    // transactions are no longer in storage.
+    let remaining: std::collections::HashSet<_> = mempool.storage().tx_ids().copied().collect();
+    let evicted: Vec<_> = inserted
+        .iter()
+        .filter(|tx| !remaining.contains(&tx.transaction.id))
+        .collect();
+    assert!(
+        !evicted.is_empty(),
+        "at least one transaction should have been evicted"
+    );
+
+    // Re-inserting an evicted transaction is rejected while it's still in
+    // the temporary rejection list.
+    let evicted_tx = evicted[0].clone();
+    let result = mempool.storage().insert(evicted_tx.clone());
+    assert!(matches!(
+        result,
+        Err(MempoolError::StorageExactTip(
+            ExactTipRejectionError::RandomlyEvicted
+        ))
+    ));
+
+    // After the rejection window elapses in chain time, the transaction can
+    // be queued again.
+    mempool
+        .storage()
+        .tick_chain_time(chain_time + chrono::Duration::seconds(61));
+    assert_eq!(
+        mempool.storage().rejection_error(&evicted_tx.transaction.id),
+        None
+    );
+
+    Ok(())
+}
+
 /// Create a new [`Mempool`] instance using mocked services.
 async fn setup(
     network: Network,